@@ -4,11 +4,14 @@ use teloxide::{
     types::{ForwardedFrom, Recipient},
     utils::command::BotCommands,
 };
+use tokio_cron_scheduler::{Job, JobScheduler};
 
 mod redis;
 
 const POWER_ON_TIME_KEY: &str = "power_on_time";
 const WAKE_UP_TIME_KEY: &str = "wake_up_time";
+const LAST_DIGEST_TIME_KEY: &str = "last_digest_time";
+const DEFAULT_HISTORY_COUNT: usize = 5;
 
 #[derive(serde::Deserialize)]
 struct EnvVariables {
@@ -16,6 +19,11 @@ struct EnvVariables {
     redis_address: String,
     bot_token: String,
     admin_user_id: u64,
+    digest_cron: String,
+    /// Smallest alert threshold a subscriber may configure, in seconds.
+    min_interval_secs: i64,
+    /// Largest alert threshold a subscriber may configure, in seconds.
+    max_time_secs: i64,
 }
 
 #[derive(BotCommands, Clone)]
@@ -23,6 +31,12 @@ struct EnvVariables {
 enum BotCommand {
     #[command(description = "reply light status")]
     Status,
+    #[command(description = "show recent power outage history")]
+    History { count: Option<usize> },
+    #[command(description = "subscribe to outage alerts of at least <duration>, e.g. 30s, 5m, 2h")]
+    Subscribe { duration: String },
+    #[command(description = "unsubscribe from outage alerts")]
+    Unsubscribe,
 }
 
 #[derive(BotCommands, Clone)]
@@ -36,6 +50,8 @@ enum AdminCommand {
 struct BotEnv {
     redis: redis::RedisClient,
     admin_user_id: u64,
+    min_interval_secs: i64,
+    max_time_secs: i64,
 }
 
 #[tokio::main]
@@ -43,9 +59,15 @@ async fn main() -> Result<(), anyhow::Error> {
     pretty_env_logger::init();
 
     let env = envy::from_env::<EnvVariables>()?;
+    ensure!(
+        env.min_interval_secs <= env.max_time_secs,
+        "min_interval_secs ({}) must be <= max_time_secs ({})",
+        env.min_interval_secs,
+        env.max_time_secs
+    );
 
     let bot = Bot::new(env.bot_token);
-    let mut redis_client = redis::RedisClient::connect(&env.redis_address)?;
+    let mut redis_client = redis::RedisClient::connect(&env.redis_address).await?;
 
     let is_admin = move |update: Message| {
         let admin_id = env.admin_user_id;
@@ -80,9 +102,15 @@ async fn main() -> Result<(), anyhow::Error> {
     // then it should reply to the message with the light status
     report_power_off_time(&bot, &mut redis_client, env.chat_id_to_report).await?;
 
+    let digest_bot = bot.clone();
+    let digest_cron = env.digest_cron.clone();
+    let chat_id_to_report = env.chat_id_to_report;
+
     let env = BotEnv {
         redis: redis_client.clone(),
         admin_user_id: env.admin_user_id,
+        min_interval_secs: env.min_interval_secs,
+        max_time_secs: env.max_time_secs,
     };
 
     let mut dispatcher = Dispatcher::builder(bot, handler)
@@ -90,7 +118,11 @@ async fn main() -> Result<(), anyhow::Error> {
         .build();
     let result = dispatcher.dispatch();
 
-    futures::future::join(result, update_up_time(redis_client)).await;
+    tokio::join!(
+        result,
+        update_up_time(redis_client.clone()),
+        run_digest_scheduler(digest_bot, redis_client, chat_id_to_report, digest_cron)
+    );
     Ok(())
 }
 
@@ -100,18 +132,27 @@ async fn report_power_off_time(
     redis: &mut redis::RedisClient,
     chat_id: i64,
 ) -> anyhow::Result<()> {
-    let stored_time = redis
-        .get(POWER_ON_TIME_KEY)
-        .unwrap_or_else(|_| chrono::Utc::now());
-    let wake_up_time: chrono::DateTime<chrono::Utc> = redis
-        .get(WAKE_UP_TIME_KEY)
-        .unwrap_or_else(|_| chrono::Utc::now());
-
     let current_time = chrono::Utc::now();
+    let stored_time = get_or(redis, POWER_ON_TIME_KEY, current_time).await?;
+    let wake_up_time = get_or(redis, WAKE_UP_TIME_KEY, current_time).await?;
+
     let time_until_wake_up = current_time - wake_up_time;
     let time_off = current_time - stored_time;
     let time_light_was_on = time_until_wake_up - time_off;
 
+    // Only a real outage (not a zero-length first boot or a sub-minute bot
+    // restart) should land in the history/digest data.
+    let is_genuine_outage = time_off >= chrono::Duration::minutes(1);
+    if is_genuine_outage {
+        redis
+            .push_power_event(&redis::PowerEvent {
+                off_at: stored_time,
+                on_at: current_time,
+                duration_secs: time_off.num_seconds(),
+            })
+            .await?;
+    }
+
     if !time_off.is_zero() && time_off < chrono::Duration::minutes(1) {
         bot.send_message(
             ChatId(chat_id),
@@ -135,7 +176,28 @@ async fn report_power_off_time(
     .await?;
 
     // Update wake up time
-    redis.set(WAKE_UP_TIME_KEY, current_time)?;
+    redis.set(WAKE_UP_TIME_KEY, current_time).await?;
+
+    let subscribers = match redis.subscribers().await {
+        Ok(subscribers) => subscribers,
+        Err(error) => {
+            log::error!("Failed to read outage subscribers, nobody was alerted: {error}");
+            Vec::new()
+        }
+    };
+
+    let time_off_secs = time_off.num_seconds();
+    for (subscriber, threshold_secs) in subscribers {
+        if time_off_secs >= threshold_secs {
+            let _ = bot
+                .send_message(
+                    ChatId(subscriber.0 as i64),
+                    format!("The power was off for {}.", duration_formatter(time_off)),
+                )
+                .await;
+        }
+    }
+
     Ok(())
 }
 
@@ -146,7 +208,8 @@ async fn update_up_time(redis_client: redis::RedisClient) {
         tokio::time::sleep(sleep_duration).await;
 
         let current_time = chrono::Utc::now();
-        let err: anyhow::Result<()> = redis_client.set(POWER_ON_TIME_KEY, current_time);
+        let err: Result<(), redis::RedisError> =
+            redis_client.set(POWER_ON_TIME_KEY, current_time).await;
         if err.is_err() {
             continue;
         }
@@ -161,13 +224,13 @@ async fn admin_handler(
 ) -> anyhow::Result<()> {
     match cmd {
         AdminCommand::Approve { user_id } => {
-            bot_env.redis.approve_user(UserId(user_id))?;
+            bot_env.redis.approve_user(UserId(user_id)).await?;
             bot.send_message(ChatId::from(msg.chat.id), "Approved user")
                 .send()
                 .await?;
         }
         AdminCommand::Disapprove { user_id } => {
-            bot_env.redis.disapprove_user(UserId(user_id))?;
+            bot_env.redis.disapprove_user(UserId(user_id)).await?;
             bot.send_message(ChatId::from(msg.chat.id), "Disapproved user")
                 .send()
                 .await?;
@@ -183,7 +246,7 @@ async fn handler(bot: Bot, msg: Message, cmd: BotCommand, bot_env: BotEnv) -> an
         .map(|user| user.id)
         .ok_or_else(|| anyhow::anyhow!("Not a message"))?;
     // Permission check
-    if !bot_env.redis.verify_approval(user_id)? && user_id.0 != bot_env.admin_user_id {
+    if !bot_env.redis.verify_approval(user_id).await? && user_id.0 != bot_env.admin_user_id {
         bot.send_message(
             ChatId::from(msg.chat.id),
             "You are not entitled to use this command",
@@ -202,7 +265,7 @@ async fn handler(bot: Bot, msg: Message, cmd: BotCommand, bot_env: BotEnv) -> an
                 return Ok(()); // Ignore old messages, power was off
             }
 
-            let stored_time = bot_env.redis.get(WAKE_UP_TIME_KEY).unwrap_or(time);
+            let stored_time = get_or(&bot_env.redis, WAKE_UP_TIME_KEY, time).await?;
 
             let time_off = time - stored_time;
             if time_off == chrono::Duration::zero() {
@@ -215,10 +278,156 @@ async fn handler(bot: Bot, msg: Message, cmd: BotCommand, bot_env: BotEnv) -> an
                 .send()
                 .await?;
         }
+        BotCommand::History { count } => {
+            let events = bot_env
+                .redis
+                .get_power_events(count.unwrap_or(DEFAULT_HISTORY_COUNT))
+                .await?;
+
+            let text = if events.is_empty() {
+                "No outages recorded yet".to_owned()
+            } else {
+                events
+                    .iter()
+                    .rev()
+                    .map(|event| {
+                        format!(
+                            "{} -> {}: off for {}",
+                            event.off_at.to_rfc3339(),
+                            event.on_at.to_rfc3339(),
+                            duration_formatter(chrono::Duration::seconds(event.duration_secs))
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            };
+
+            bot.send_message(msg.chat.id, text)
+                .reply_to_message_id(msg.id)
+                .send()
+                .await?;
+        }
+        BotCommand::Subscribe { duration } => {
+            let Some(threshold_secs) = parse_duration_secs(&duration) else {
+                bot.send_message(
+                    msg.chat.id,
+                    "Could not parse duration, expected forms like 30s, 5m, 2h",
+                )
+                .reply_to_message_id(msg.id)
+                .send()
+                .await?;
+                return Ok(());
+            };
+            let threshold_secs =
+                threshold_secs.clamp(bot_env.min_interval_secs, bot_env.max_time_secs);
+
+            bot_env.redis.subscribe(user_id, threshold_secs).await?;
+
+            bot.send_message(
+                msg.chat.id,
+                format!(
+                    "Subscribed to outage alerts of at least {}",
+                    duration_formatter(chrono::Duration::seconds(threshold_secs))
+                ),
+            )
+            .reply_to_message_id(msg.id)
+            .send()
+            .await?;
+        }
+        BotCommand::Unsubscribe => {
+            bot_env.redis.unsubscribe(user_id).await?;
+            bot.send_message(msg.chat.id, "Unsubscribed from outage alerts")
+                .reply_to_message_id(msg.id)
+                .send()
+                .await?;
+        }
     }
     Ok(())
 }
 
+/// Runs the recurring uptime/outage digest on `cron`, posting a summary to `chat_id`.
+async fn run_digest_scheduler(
+    bot: Bot,
+    redis: redis::RedisClient,
+    chat_id: i64,
+    cron: String,
+) -> anyhow::Result<()> {
+    if let Err(error) = run_digest_scheduler_inner(bot, redis, chat_id, cron).await {
+        log::error!("Uptime digest scheduler failed to start: {error}");
+        return Err(error);
+    }
+
+    // The scheduler runs its jobs on its own background task; this future stays
+    // pending for the lifetime of the bot.
+    std::future::pending().await
+}
+
+async fn run_digest_scheduler_inner(
+    bot: Bot,
+    redis: redis::RedisClient,
+    chat_id: i64,
+    cron: String,
+) -> anyhow::Result<()> {
+    let scheduler = JobScheduler::new().await?;
+
+    scheduler
+        .add(Job::new_async(cron.as_str(), move |_uuid, _scheduler| {
+            let bot = bot.clone();
+            let redis = redis.clone();
+            Box::pin(async move {
+                if let Err(error) = send_digest(&bot, &redis, chat_id).await {
+                    log::error!("Failed to send uptime digest: {error}");
+                }
+            })
+        })?)
+        .await?;
+
+    scheduler.start().await?;
+    Ok(())
+}
+
+/// Aggregates power events since the last digest and posts the summary, then
+/// records the new `last_digest_time` so the next window doesn't overlap.
+async fn send_digest(bot: &Bot, redis: &redis::RedisClient, chat_id: i64) -> anyhow::Result<()> {
+    let now = chrono::Utc::now();
+    let last_digest_time =
+        get_or(redis, LAST_DIGEST_TIME_KEY, now - chrono::Duration::weeks(1)).await?;
+
+    let events: Vec<_> = redis
+        .get_all_power_events()
+        .await?
+        .into_iter()
+        .filter(|event| event.on_at > last_digest_time)
+        .collect();
+
+    // Clip each event to the digest window: an outage that started before the
+    // last digest only counts from `last_digest_time` onward, so it can't push
+    // the reported uptime below 0% when it spans the window boundary.
+    let total_outage = events.iter().fold(chrono::Duration::zero(), |acc, event| {
+        let clipped_off_at = event.off_at.max(last_digest_time);
+        acc + (event.on_at - clipped_off_at).max(chrono::Duration::zero())
+    });
+    let window = now - last_digest_time;
+    let uptime_percent = if window > chrono::Duration::zero() {
+        100.0 - total_outage.num_seconds() as f64 / window.num_seconds() as f64 * 100.0
+    } else {
+        100.0
+    };
+
+    bot.send_message(
+        ChatId(chat_id),
+        format!(
+            "Grid was up {uptime_percent:.1}% since the last digest.\n{} outages totaling {}.",
+            events.len(),
+            duration_formatter(total_outage)
+        ),
+    )
+    .await?;
+
+    redis.set(LAST_DIGEST_TIME_KEY, now).await?;
+    Ok(())
+}
+
 async fn forward_handler(bot: Bot, msg: Message) -> anyhow::Result<()> {
     // Safe to unwrap because we only register this handler for forwarded messages
     let user_id = msg.forward().unwrap().from.clone();
@@ -237,6 +446,34 @@ async fn forward_handler(bot: Bot, msg: Message) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Reads a stored timestamp, defaulting to `fallback` only when the key is
+/// genuinely absent. Any other error (a dropped connection, a corrupt value)
+/// is propagated instead of being silently papered over with `fallback`.
+async fn get_or(
+    redis: &redis::RedisClient,
+    key: &str,
+    fallback: chrono::DateTime<chrono::Utc>,
+) -> Result<chrono::DateTime<chrono::Utc>, redis::RedisError> {
+    match redis.get(key).await {
+        Ok(time) => Ok(time),
+        Err(redis::RedisError::NotFound) => Ok(fallback),
+        Err(error) => Err(error),
+    }
+}
+
+/// Parses durations like `30s`, `5m`, `2h` into a number of seconds.
+fn parse_duration_secs(input: &str) -> Option<i64> {
+    let unit = input.chars().last()?;
+    let value: i64 = input[..input.len() - unit.len_utf8()].parse().ok()?;
+    let multiplier = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 3600,
+        _ => return None,
+    };
+    value.checked_mul(multiplier)
+}
+
 fn duration_formatter(duration: chrono::Duration) -> String {
     let mut result = String::new();
     let days = duration.num_days();
@@ -1,58 +1,183 @@
-use redis::Commands;
+use bb8_redis::{bb8, RedisConnectionManager};
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
 use teloxide::types::UserId;
 
+const POWER_EVENTS_KEY: &str = "power_events";
+const MAX_POWER_EVENTS: isize = 200;
+const SUBSCRIBERS_KEY: &str = "subscribers";
+const SUBSCRIPTION_THRESHOLD_PREFIX: &str = "subscription_threshold:";
+
+/// Errors that can occur while talking to Redis, distinct enough that callers
+/// can tell a missing key apart from a connection failure instead of treating
+/// both as "default to now".
+#[derive(Debug, thiserror::Error)]
+pub enum RedisError {
+    #[error("key not found")]
+    NotFound,
+    #[error("redis connection error: {0}")]
+    Connection(#[from] redis::RedisError),
+    #[error("failed to parse stored value: {0}")]
+    Parse(String),
+    #[error("failed to obtain a pooled connection: {0}")]
+    Pool(#[from] bb8::RunError<redis::RedisError>),
+}
+
+/// A pooled, ready-to-use Redis connection.
+type RedisConn<'a> = bb8::PooledConnection<'a, RedisConnectionManager>;
+
+/// A single recorded power outage, pushed onto the `power_events` list on startup.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PowerEvent {
+    pub off_at: chrono::DateTime<chrono::Utc>,
+    pub on_at: chrono::DateTime<chrono::Utc>,
+    pub duration_secs: i64,
+}
+
 #[derive(Clone)]
 pub struct RedisClient {
-    client: redis::Client,
+    pool: bb8::Pool<RedisConnectionManager>,
 }
 
 impl RedisClient {
-    pub fn connect(redis_addr: &str) -> anyhow::Result<Self> {
-        let redis_client = redis::Client::open(redis_addr)?;
+    pub async fn connect(redis_addr: &str) -> anyhow::Result<Self> {
+        let manager = RedisConnectionManager::new(redis_addr)?;
+        let pool = bb8::Pool::builder().build(manager).await?;
+
+        Ok(Self { pool })
+    }
 
-        Ok(Self {
-            client: redis_client,
-        })
+    async fn conn(&self) -> Result<RedisConn<'_>, RedisError> {
+        Ok(self.pool.get().await?)
     }
 
-    pub fn get(&self, key: &str) -> Result<chrono::DateTime<chrono::Utc>, anyhow::Error> {
-        let mut connection = self.client.get_connection()?;
-        let value: String = connection.get(key)?;
-        let time = chrono::DateTime::parse_from_rfc3339(&value)?;
+    pub async fn get(&self, key: &str) -> Result<chrono::DateTime<chrono::Utc>, RedisError> {
+        let mut connection = self.conn().await?;
+        let value: Option<String> = connection.get(key).await?;
+        let value = value.ok_or(RedisError::NotFound)?;
+
+        let time = chrono::DateTime::parse_from_rfc3339(&value)
+            .map_err(|error| RedisError::Parse(error.to_string()))?;
         // Convert fixed offset to UTC
         let time = chrono::DateTime::<chrono::Utc>::from_utc(time.naive_utc(), chrono::Utc);
         Ok(time)
     }
 
-    pub fn set(
+    pub async fn set(
         &self,
         key: &str,
         value: chrono::DateTime<chrono::Utc>,
-    ) -> Result<(), anyhow::Error> {
-        let mut connection = self.client.get_connection()?;
+    ) -> Result<(), RedisError> {
+        let mut connection = self.conn().await?;
 
         let value = value.to_rfc3339();
-        connection.set(key, value)?;
+        connection.set(key, value).await?;
         Ok(())
     }
 
-    pub fn verify_approval(&self, user_id: UserId) -> Result<bool, anyhow::Error> {
-        let mut connection = self.client.get_connection()?;
-        let value: Option<String> = connection.get(user_id.to_string())?;
+    pub async fn verify_approval(&self, user_id: UserId) -> Result<bool, RedisError> {
+        let mut connection = self.conn().await?;
+        let value: Option<String> = connection.get(user_id.to_string()).await?;
         Ok(value == Some("approved".to_string()))
     }
 
-    fn manage_user(&self, user_id: UserId, value: &str) -> Result<(), anyhow::Error> {
-        let mut connection = self.client.get_connection()?;
-        connection.set(user_id.to_string(), value)?;
+    async fn manage_user(&self, user_id: UserId, value: &str) -> Result<(), RedisError> {
+        let mut connection = self.conn().await?;
+        connection.set(user_id.to_string(), value).await?;
+        Ok(())
+    }
+
+    pub async fn approve_user(&self, user_id: UserId) -> Result<(), RedisError> {
+        self.manage_user(user_id, "approved").await
+    }
+
+    pub async fn disapprove_user(&self, user_id: UserId) -> Result<(), RedisError> {
+        self.manage_user(user_id, "disapproved").await
+    }
+
+    /// Appends an outage to the power event history, trimming it to the last `MAX_POWER_EVENTS`.
+    pub async fn push_power_event(&self, event: &PowerEvent) -> Result<(), RedisError> {
+        let mut connection = self.conn().await?;
+        let value =
+            serde_json::to_string(event).map_err(|error| RedisError::Parse(error.to_string()))?;
+        connection.rpush(POWER_EVENTS_KEY, value).await?;
+        connection
+            .ltrim(POWER_EVENTS_KEY, -MAX_POWER_EVENTS, -1)
+            .await?;
+        Ok(())
+    }
+
+    /// Returns the last `count` recorded power events, oldest first.
+    pub async fn get_power_events(&self, count: usize) -> Result<Vec<PowerEvent>, RedisError> {
+        if count == 0 {
+            return Ok(Vec::new());
+        }
+
+        let mut connection = self.conn().await?;
+        let values: Vec<String> = connection
+            .lrange(POWER_EVENTS_KEY, -(count as isize), -1)
+            .await?;
+        parse_power_events(&values)
+    }
+
+    /// Returns the full recorded power event history, oldest first.
+    pub async fn get_all_power_events(&self) -> Result<Vec<PowerEvent>, RedisError> {
+        let mut connection = self.conn().await?;
+        let values: Vec<String> = connection.lrange(POWER_EVENTS_KEY, 0, -1).await?;
+        parse_power_events(&values)
+    }
+
+    /// Subscribes `user_id` to outage alerts of at least `threshold_secs`.
+    pub async fn subscribe(&self, user_id: UserId, threshold_secs: i64) -> Result<(), RedisError> {
+        let mut connection = self.conn().await?;
+        connection.sadd(SUBSCRIBERS_KEY, user_id.0).await?;
+        connection
+            .set(
+                format!("{SUBSCRIPTION_THRESHOLD_PREFIX}{}", user_id.0),
+                threshold_secs,
+            )
+            .await?;
         Ok(())
     }
 
-    pub fn approve_user(&self, user_id: UserId) -> Result<(), anyhow::Error> {
-        self.manage_user(user_id, "approved")
+    pub async fn unsubscribe(&self, user_id: UserId) -> Result<(), RedisError> {
+        let mut connection = self.conn().await?;
+        connection.srem(SUBSCRIBERS_KEY, user_id.0).await?;
+        connection
+            .del(format!("{SUBSCRIPTION_THRESHOLD_PREFIX}{}", user_id.0))
+            .await?;
+        Ok(())
     }
 
-    pub fn disapprove_user(&self, user_id: UserId) -> Result<(), anyhow::Error> {
-        self.manage_user(user_id, "disapproved")
+    /// Returns every subscriber along with their configured alert threshold, in seconds.
+    pub async fn subscribers(&self) -> Result<Vec<(UserId, i64)>, RedisError> {
+        let mut connection = self.conn().await?;
+        let user_ids: Vec<u64> = connection.smembers(SUBSCRIBERS_KEY).await?;
+
+        let mut subscribers = Vec::with_capacity(user_ids.len());
+        for user_id in user_ids {
+            let threshold_secs = match connection
+                .get::<_, Option<i64>>(format!("{SUBSCRIPTION_THRESHOLD_PREFIX}{user_id}"))
+                .await
+            {
+                Ok(Some(threshold_secs)) => threshold_secs,
+                Ok(None) => i64::MAX,
+                Err(error) => {
+                    log::error!(
+                        "Failed to read subscription threshold for user {user_id}: {error}"
+                    );
+                    i64::MAX
+                }
+            };
+            subscribers.push((UserId(user_id), threshold_secs));
+        }
+        Ok(subscribers)
     }
 }
+
+fn parse_power_events(values: &[String]) -> Result<Vec<PowerEvent>, RedisError> {
+    values
+        .iter()
+        .map(|value| serde_json::from_str(value).map_err(|error| RedisError::Parse(error.to_string())))
+        .collect()
+}